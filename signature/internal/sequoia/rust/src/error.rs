@@ -10,8 +10,35 @@ pub enum SequoiaErrorKind {
     Unknown,
     InvalidArgument,
     IoError,
+    CardNotPresent,
+    BadPin,
 }
 
+/// Errors originating from a hardware (OpenPGP card) signing backend.
+///
+/// These are surfaced as dedicated [`SequoiaErrorKind`] values so that
+/// callers can tell "no card plugged in" and "wrong PIN" apart from a
+/// generic failure and re-prompt the user accordingly.
+#[derive(Debug)]
+pub enum SmartcardError {
+    /// No card backend advertised the requested key, i.e. the card is
+    /// most likely not inserted or the reader is unplugged.
+    CardNotPresent,
+    /// The card rejected the supplied PIN.
+    BadPin,
+}
+
+impl std::fmt::Display for SmartcardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SmartcardError::CardNotPresent => write!(f, "no OpenPGP card is present"),
+            SmartcardError::BadPin => write!(f, "the card rejected the PIN"),
+        }
+    }
+}
+
+impl std::error::Error for SmartcardError {}
+
 #[repr(C)]
 pub struct SequoiaError {
     kind: SequoiaErrorKind,
@@ -35,6 +62,11 @@ pub unsafe fn set_error_from(err_ptr: *mut *mut SequoiaError, err: anyhow::Error
     if !err_ptr.is_null() {
         let kind = if err.is::<io::Error>() {
             SequoiaErrorKind::IoError
+        } else if let Some(sc) = err.downcast_ref::<SmartcardError>() {
+            match sc {
+                SmartcardError::CardNotPresent => SequoiaErrorKind::CardNotPresent,
+                SmartcardError::BadPin => SequoiaErrorKind::BadPin,
+            }
         } else {
             SequoiaErrorKind::Unknown
         };
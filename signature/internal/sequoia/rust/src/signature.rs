@@ -6,7 +6,12 @@ use libc::{c_char, size_t};
 use openpgp::cert::prelude::*;
 use openpgp::parse::{stream::*, Parse};
 use openpgp::policy::StandardPolicy;
-use openpgp::serialize::stream::{LiteralWriter, Message, Signer};
+use openpgp::serialize::stream::padding::{padme, Padder};
+use openpgp::serialize::stream::{
+    Armorer, Compressor, Encryptor, LiteralWriter, Message, Recipient, Signer,
+};
+use openpgp::serialize::Serialize as _;
+use openpgp::types::{NotationDataFlags, RevocationStatus, SymmetricAlgorithm};
 use openpgp::KeyHandle;
 use sequoia_cert_store::{Store as _, StoreUpdate as _};
 use sequoia_openpgp as openpgp;
@@ -19,13 +24,68 @@ use std::path::Path;
 use std::ptr;
 use std::slice;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{set_error_from, SequoiaError};
+use crate::{set_error_from, SequoiaError, SmartcardError};
+
+/// Identifier of the software key backend shipped with sequoia-keystore.
+const SOFTKEYS_BACKEND: &str = "softkeys";
+
+/// Public-key cipher suite for a freshly generated key, mirroring the
+/// choices offered by `sq key generate`.
+#[repr(C)]
+pub enum SequoiaCipherSuite {
+    Cv25519,
+    Rsa2k,
+    Rsa3k,
+    Rsa4k,
+}
+
+/// Default set of HKPS keyservers consulted during a refresh.
+const DEFAULT_KEYSERVERS: &[&str] = &["hkps://keys.openpgp.org"];
+
+/// Controls which remote sources, if any, a certificate refresh may
+/// contact. Air-gapped deployments select [`SequoiaNetworkPolicy::Offline`]
+/// to disable all network access.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum SequoiaNetworkPolicy {
+    /// Never touch the network; a refresh is a no-op.
+    Offline,
+    /// Fetch updates from HKPS keyservers only.
+    Keyservers,
+    /// Fetch updates from Web Key Directory only.
+    Wkd,
+    /// Fetch updates from both keyservers and WKD.
+    All,
+}
+
+impl SequoiaNetworkPolicy {
+    fn use_keyservers(self) -> bool {
+        matches!(self, SequoiaNetworkPolicy::Keyservers | SequoiaNetworkPolicy::All)
+    }
+
+    fn use_wkd(self) -> bool {
+        matches!(self, SequoiaNetworkPolicy::Wkd | SequoiaNetworkPolicy::All)
+    }
+}
+
+impl From<SequoiaCipherSuite> for openpgp::cert::CipherSuite {
+    fn from(suite: SequoiaCipherSuite) -> Self {
+        match suite {
+            SequoiaCipherSuite::Cv25519 => openpgp::cert::CipherSuite::Cv25519,
+            SequoiaCipherSuite::Rsa2k => openpgp::cert::CipherSuite::RSA2k,
+            SequoiaCipherSuite::Rsa3k => openpgp::cert::CipherSuite::RSA3k,
+            SequoiaCipherSuite::Rsa4k => openpgp::cert::CipherSuite::RSA4k,
+        }
+    }
+}
 
 pub struct SequoiaMechanism<'a> {
     keystore: sequoia_keystore::Keystore,
     certstore: Arc<sequoia_cert_store::CertStore<'a>>,
     policy: StandardPolicy<'a>,
+    network_policy: SequoiaNetworkPolicy,
 }
 
 impl<'a> SequoiaMechanism<'a> {
@@ -50,6 +110,7 @@ impl<'a> SequoiaMechanism<'a> {
             keystore,
             certstore: Arc::new(certstore),
             policy,
+            network_policy: SequoiaNetworkPolicy::All,
         })
     }
 
@@ -63,23 +124,88 @@ impl<'a> SequoiaMechanism<'a> {
             keystore: sequoia_keystore::Keystore::connect(&context)?,
             certstore,
             policy,
+            network_policy: SequoiaNetworkPolicy::All,
         })
     }
 
-    fn import_keys(&mut self, blob: &[u8]) -> Result<SequoiaImportResult, anyhow::Error> {
-        let mut softkeys = None;
+    fn softkeys(&mut self) -> Result<sequoia_keystore::Backend, anyhow::Error> {
         for mut backend in self.keystore.backends()?.into_iter() {
-            if backend.id()? == "softkeys" {
-                softkeys = Some(backend);
-                break;
+            if backend.id()? == SOFTKEYS_BACKEND {
+                return Ok(backend);
+            }
+        }
+        Err(anyhow::anyhow!("softkeys backend is not configured."))
+    }
+
+    fn list_backends(&mut self) -> Result<SequoiaBackendList, anyhow::Error> {
+        let mut backends = vec![];
+        for mut backend in self.keystore.backends()?.into_iter() {
+            backends.push(CString::new(backend.id()?.into_bytes())?);
+        }
+        Ok(SequoiaBackendList { backends })
+    }
+
+    /// Enumerate every key known to the keystore, across both the
+    /// `softkeys` backend and any connected OpenPGP card backends,
+    /// recording which backend holds it and whether it is PIN/password
+    /// protected.
+    fn list_keys(&mut self) -> Result<SequoiaKeyList, anyhow::Error> {
+        let mut keys = vec![];
+        for mut backend in self.keystore.backends()?.into_iter() {
+            // A flaky card (unplugged mid-scan, reader glitch) must not
+            // hide the keys held by the other backends.
+            let backend_id = match backend.id() {
+                Ok(backend_id) => backend_id,
+                Err(err) => {
+                    log::info!("Skipping unidentifiable backend: {}", err);
+                    continue;
+                }
+            };
+            let backend_keys = match backend.list_keys() {
+                Ok(backend_keys) => backend_keys,
+                Err(err) => {
+                    log::info!("Skipping backend {}: {}", backend_id, err);
+                    continue;
+                }
+            };
+            for mut key in backend_keys {
+                keys.push(SequoiaKeyInfo {
+                    backend: CString::new(backend_id.clone().into_bytes())?,
+                    fingerprint: CString::new(key.fingerprint().to_hex().into_bytes())?,
+                    pin_needed: matches!(
+                        key.locked(),
+                        Ok(sequoia_keystore::Protection::Password(_))
+                    ),
+                });
             }
         }
+        Ok(SequoiaKeyList { keys })
+    }
 
-        let mut softkeys = if let Some(softkeys) = softkeys {
-            softkeys
-        } else {
-            return Err(anyhow::anyhow!("softkeys backend is not configured."));
+    /// Return whether `handle` is held by the `softkeys` backend.
+    ///
+    /// A signing subkey that is known to the cert store but absent from
+    /// `softkeys` is assumed to live on an OpenPGP card; checking for
+    /// software membership (rather than listing card keys) means the
+    /// answer is still correct when the card is unplugged, and the check
+    /// itself does no card I/O.
+    fn key_on_softkeys(&mut self, handle: &KeyHandle) -> Result<bool, anyhow::Error> {
+        // A keystore may be card-only, with no softkeys backend at all;
+        // in that case the key is definitely not a software key.
+        let mut softkeys = match self.softkeys() {
+            Ok(softkeys) => softkeys,
+            Err(_) => return Ok(false),
         };
+        for mut key in softkeys.list_keys()? {
+            if handle.aliases(KeyHandle::from(key.fingerprint())) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn import_keys(&mut self, blob: &[u8]) -> Result<SequoiaImportResult, anyhow::Error> {
+        let mut softkeys = self.softkeys()?;
 
         let mut key_handles = vec![];
         for r in CertParser::from_bytes(blob)? {
@@ -94,18 +220,170 @@ impl<'a> SequoiaMechanism<'a> {
             let _ = softkeys.import(&cert)?;
 
             key_handles.push(CString::new(cert.fingerprint().to_hex().as_bytes()).unwrap());
-            self.certstore
-                .update(Arc::new(sequoia_cert_store::LazyCert::from(cert)))?;
+            // The keystore holds the secret material; the cert store is a
+            // public directory, so never persist private keys there.
+            self.certstore.update(Arc::new(sequoia_cert_store::LazyCert::from(
+                cert.strip_secret_key_material(),
+            )))?;
         }
         Ok(SequoiaImportResult { key_handles })
     }
 
-    fn sign(
+    /// Generate a fresh signing identity with `CertBuilder`, import the
+    /// resulting secret key into the `softkeys` backend and its public
+    /// half into the cert store, and return a handle carrying the new
+    /// fingerprint.
+    ///
+    /// With no User IDs the cert is anchored by a direct-key signature,
+    /// matching `sq key generate`. A `validity_seconds` of 0 creates a
+    /// non-expiring key.
+    fn generate_key(
+        &mut self,
+        userids: &[&str],
+        validity_seconds: u64,
+        cipher_suite: SequoiaCipherSuite,
+    ) -> Result<SequoiaImportResult, anyhow::Error> {
+        let mut builder = CertBuilder::new()
+            .set_cipher_suite(cipher_suite.into())
+            .add_signing_subkey();
+
+        for userid in userids {
+            builder = builder.add_userid(*userid);
+        }
+
+        if validity_seconds > 0 {
+            builder = builder.set_validity_period(Some(Duration::from_secs(validity_seconds)));
+        }
+
+        let (cert, _) = builder.generate()?;
+
+        // Route the new key through the regular import path so it lands
+        // in the softkeys backend and the cert store in one place.
+        let mut tsk = vec![];
+        cert.as_tsk().serialize(&mut tsk)?;
+        self.import_keys(&tsk)
+    }
+
+    fn set_network_policy(&mut self, network_policy: SequoiaNetworkPolicy) {
+        self.network_policy = network_policy;
+    }
+
+    /// Refresh every certificate in the store from its remote sources.
+    ///
+    /// For each cert we fetch fresh copies over HKPS keyservers and/or
+    /// WKD (as permitted by the configured [`SequoiaNetworkPolicy`]),
+    /// merge them into the local copy with [`Cert::merge_public`], and
+    /// write the result back through [`StoreUpdate`]. Because `verify()`
+    /// reads from this same store, a revocation picked up here causes a
+    /// subsequent verification against the revoked signer to fail.
+    ///
+    /// Returns the number of certs that changed and, of those, how many
+    /// are now revoked.
+    fn refresh_certs(&mut self, timeout_secs: u64) -> Result<SequoiaRefreshResult, anyhow::Error> {
+        if matches!(self.network_policy, SequoiaNetworkPolicy::Offline) {
+            return Ok(SequoiaRefreshResult {
+                updated: 0,
+                revoked: 0,
+            });
+        }
+
+        let network_policy = self.network_policy;
+        let policy = self.policy.clone();
+        let timeout = Duration::from_secs(timeout_secs);
+
+        let certs = self
+            .certstore
+            .certs()
+            .filter_map(|lc| lc.to_cert().ok().cloned())
+            .collect::<Vec<Cert>>();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut updated = 0;
+        let mut revoked = 0;
+        for cert in certs {
+            let merged = runtime.block_on(Self::fetch_and_merge(network_policy, timeout, &cert))?;
+
+            // Skip certs that did not gain anything from the remote copy.
+            if merged.fingerprint() == cert.fingerprint()
+                && merged.clone().into_packets().count() == cert.clone().into_packets().count()
+            {
+                continue;
+            }
+
+            if matches!(
+                merged.revocation_status(&policy, None),
+                RevocationStatus::Revoked(_)
+            ) {
+                revoked += 1;
+            }
+
+            self.certstore
+                .update(Arc::new(sequoia_cert_store::LazyCert::from(merged)))?;
+            updated += 1;
+        }
+
+        Ok(SequoiaRefreshResult { updated, revoked })
+    }
+
+    async fn fetch_and_merge(
+        network_policy: SequoiaNetworkPolicy,
+        timeout: Duration,
+        cert: &Cert,
+    ) -> Result<Cert, anyhow::Error> {
+        let mut merged = cert.clone();
+
+        if network_policy.use_keyservers() {
+            for url in DEFAULT_KEYSERVERS {
+                let mut server = match sequoia_net::KeyServer::new(url) {
+                    Ok(server) => server,
+                    Err(err) => {
+                        log::info!("Ignoring keyserver {}: {}", url, err);
+                        continue;
+                    }
+                };
+                let request = server.get(cert.key_handle());
+                if let Ok(Ok(results)) = tokio::time::timeout(timeout, request).await {
+                    for result in results {
+                        if let Ok(remote) = result {
+                            merged = merged.merge_public(remote)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if network_policy.use_wkd() {
+            for uid in cert.userids() {
+                let email = match uid.userid().email2() {
+                    Ok(Some(email)) => email.to_string(),
+                    _ => continue,
+                };
+                let request = sequoia_net::wkd::get(&email);
+                if let Ok(Ok(results)) = tokio::time::timeout(timeout, request).await {
+                    for remote in results {
+                        merged = merged.merge_public(remote)?;
+                    }
+                }
+            }
+        }
+
+        Ok(merged)
+    }
+
+    /// Resolve `key_handle` to an unlocked signing key from the keystore.
+    ///
+    /// Shared by the inline and detached signing paths: it finds the
+    /// signing subkey in the cert store, locates it in the keystore
+    /// (software or card), and applies `password` as the key password or
+    /// card PIN.
+    fn signing_keys(
         &mut self,
         key_handle: &str,
         password: Option<&str>,
-        data: &[u8],
-    ) -> Result<Vec<u8>, anyhow::Error> {
+    ) -> Result<Vec<sequoia_keystore::Key>, anyhow::Error> {
         let primary_key_handle: KeyHandle = key_handle.parse()?;
         let certs = self
             .certstore
@@ -132,19 +410,55 @@ impl<'a> SequoiaMechanism<'a> {
             ));
         }
 
-        let mut keys = self.keystore.find_key(signing_key_handles[0].clone())?;
+        let signing_handle = signing_key_handles[0].clone();
+        let on_card = !self.key_on_softkeys(&signing_handle)?;
+
+        let mut keys = self.keystore.find_key(signing_handle)?;
 
         if keys.is_empty() {
+            // The signing subkey is known from the cert store but no
+            // backend currently holds it; for a card-resident key this
+            // means the card is not inserted.
+            if on_card {
+                return Err(SmartcardError::CardNotPresent.into());
+            }
             return Err(anyhow::anyhow!("No matching key in keystore"));
         }
         if let Some(password) = password {
-            keys[0].unlock(password.into())?;
+            keys[0].unlock(password.into()).map_err(|err| {
+                if on_card {
+                    SmartcardError::BadPin.into()
+                } else {
+                    err
+                }
+            })?;
         }
 
+        Ok(keys)
+    }
+
+    fn sign(
+        &mut self,
+        key_handle: &str,
+        password: Option<&str>,
+        data: &[u8],
+        notations: &[(&str, &str)],
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let mut keys = self.signing_keys(key_handle, password)?;
+
         let mut sink = vec![];
         {
             let message = Message::new(&mut sink);
-            let message = Signer::new(message, &mut keys[0])?.build()?;
+            let mut signer = Signer::new(message, &mut keys[0])?;
+            for (name, value) in notations {
+                signer = signer.add_notation(
+                    *name,
+                    *value,
+                    NotationDataFlags::empty().set_human_readable(),
+                    false,
+                )?;
+            }
+            let message = signer.build()?;
             let mut message = LiteralWriter::new(message).build()?;
             message.write_all(data)?;
             message.finalize()?;
@@ -152,6 +466,67 @@ impl<'a> SequoiaMechanism<'a> {
         Ok(sink)
     }
 
+    /// Produce a detached signature over `data`: just the signature
+    /// packet, with no literal layer wrapping the payload. Optionally
+    /// ASCII-armor the output.
+    fn sign_detached(
+        &mut self,
+        key_handle: &str,
+        password: Option<&str>,
+        data: &[u8],
+        armor: bool,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let mut keys = self.signing_keys(key_handle, password)?;
+
+        let mut sink = vec![];
+        {
+            let message = Message::new(&mut sink);
+            let message = if armor {
+                Armorer::new(message)
+                    .kind(openpgp::armor::Kind::Signature)
+                    .build()?
+            } else {
+                message
+            };
+            let mut message = Signer::new(message, &mut keys[0])?.detached().build()?;
+            message.write_all(data)?;
+            message.finalize()?;
+        }
+        Ok(sink)
+    }
+
+    fn verify_detached(
+        &mut self,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<SequoiaVerificationResult, anyhow::Error> {
+        if signature.is_empty() {
+            return Err(anyhow::anyhow!("empty signature"));
+        }
+
+        let h = Helper {
+            certstore: self.certstore.clone(),
+            signer: Default::default(),
+            signatures: Default::default(),
+        };
+        let mut policy = ConfiguredStandardPolicy::new();
+        policy.parse_default_config()?;
+        let policy = policy.build();
+
+        let mut v = DetachedVerifierBuilder::from_bytes(signature)?.with_policy(&policy, None, h)?;
+        v.verify_bytes(data)?;
+
+        let signatures = std::mem::take(&mut v.helper_mut().signatures);
+        match &v.helper_ref().signer {
+            Some(signer) => Ok(SequoiaVerificationResult {
+                content: data.to_vec(),
+                signer: CString::new(signer.fingerprint().to_hex().as_bytes()).unwrap(),
+                signatures,
+            }),
+            None => Err(anyhow::anyhow!("No valid signature")),
+        }
+    }
+
     fn verify(&mut self, signature: &[u8]) -> Result<SequoiaVerificationResult, anyhow::Error> {
         if signature.is_empty() {
             return Err(anyhow::anyhow!("empty signature"));
@@ -160,6 +535,7 @@ impl<'a> SequoiaMechanism<'a> {
         let h = Helper {
             certstore: self.certstore.clone(),
             signer: Default::default(),
+            signatures: Default::default(),
         };
         let mut policy = ConfiguredStandardPolicy::new();
         policy.parse_default_config()?;
@@ -171,31 +547,153 @@ impl<'a> SequoiaMechanism<'a> {
 
         assert!(v.message_processed());
 
+        let signatures = std::mem::take(&mut v.helper_mut().signatures);
         match &v.helper_ref().signer {
             Some(signer) => Ok(SequoiaVerificationResult {
                 content,
                 signer: CString::new(signer.fingerprint().to_hex().as_bytes()).unwrap(),
+                signatures,
             }),
             None => Err(anyhow::anyhow!("No valid signature")),
         }
     }
+
+    /// Encrypt `data` to each recipient, resolving every fingerprint in
+    /// `recipients` through the cert store to its transport-encryption
+    /// subkeys. The plaintext length is masked with `Padder`/`padme`
+    /// before encryption.
+    fn encrypt(&mut self, recipients: &[&str], data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut certs = vec![];
+        for recipient in recipients {
+            let handle: KeyHandle = recipient.parse()?;
+            let matches = self
+                .certstore
+                .lookup_by_cert_or_subkey(&handle)
+                .with_context(|| format!("Failed to load {} from certificate store", recipient))?;
+            for lc in matches {
+                let cert = lc.to_cert()?.clone();
+                // A fingerprint may be passed twice, or two handles may
+                // resolve to the same cert; encrypt to each cert once.
+                if !certs.iter().any(|c: &Cert| c.fingerprint() == cert.fingerprint()) {
+                    certs.push(cert);
+                }
+            }
+        }
+
+        let mut recipient_keys: Vec<Recipient> = vec![];
+        for cert in &certs {
+            for ka in cert
+                .keys()
+                .with_policy(&self.policy, None)
+                .alive()
+                .revoked(false)
+                .supported()
+                .for_transport_encryption()
+            {
+                recipient_keys.push(ka.key().into());
+            }
+        }
+
+        if recipient_keys.is_empty() {
+            return Err(anyhow::anyhow!("No encryption-capable key for recipients"));
+        }
+
+        let mut sink = vec![];
+        {
+            let message = Message::new(&mut sink);
+            let message = Encryptor::for_recipients(message, recipient_keys).build()?;
+            // Pad outside the compressor: compress first, then mask the
+            // compressed length, otherwise padme's padding is simply
+            // squeezed back out by the compressor.
+            let message = Padder::new(message, padme).build()?;
+            let message = Compressor::new(message).build()?;
+            let mut message = LiteralWriter::new(message).build()?;
+            message.write_all(data)?;
+            message.finalize()?;
+        }
+        Ok(sink)
+    }
+
+    /// Decrypt an OpenPGP message, unlocking the matching decryption key
+    /// from the keystore (software or card) with `password`. Returns the
+    /// plaintext and the fingerprint of the key that decrypted it.
+    fn decrypt(
+        &mut self,
+        ciphertext: &[u8],
+        password: Option<&str>,
+    ) -> Result<SequoiaDecryptionResult, anyhow::Error> {
+        let mut policy = ConfiguredStandardPolicy::new();
+        policy.parse_default_config()?;
+        let policy = policy.build();
+
+        let h = DecryptHelper {
+            certstore: self.certstore.clone(),
+            keystore: &mut self.keystore,
+            password,
+            fingerprint: None,
+        };
+
+        let mut d = DecryptorBuilder::from_bytes(ciphertext)?.with_policy(&policy, None, h)?;
+        let mut content = Vec::new();
+        d.read_to_end(&mut content)?;
+
+        match &d.helper_ref().fingerprint {
+            Some(fingerprint) => Ok(SequoiaDecryptionResult {
+                content,
+                fingerprint: CString::new(fingerprint.to_hex().as_bytes()).unwrap(),
+            }),
+            None => Err(anyhow::anyhow!("No matching decryption key")),
+        }
+    }
+}
+
+/// Look every handle in `ids` up in the cert store, used by both the
+/// verification and decryption helpers to satisfy `get_certs`.
+fn lookup_certs(
+    certstore: &sequoia_cert_store::CertStore,
+    ids: &[openpgp::KeyHandle],
+) -> openpgp::Result<Vec<openpgp::Cert>> {
+    let mut certs = Vec::new();
+    for id in ids {
+        for lc in certstore.lookup_by_cert_or_subkey(id)? {
+            certs.push(lc.to_cert()?.clone());
+        }
+    }
+    Ok(certs)
 }
 
 struct Helper<'a> {
     certstore: Arc<sequoia_cert_store::CertStore<'a>>,
     signer: Option<openpgp::Cert>,
+    signatures: Vec<VerifiedSignature>,
+}
+
+/// Turn a good signature into a retained record, capturing the signer's
+/// fingerprint, the signature creation time and any notation-data
+/// subpackets so callers can inspect every signer rather than only the
+/// first.
+fn record_signature(good: &GoodChecksum) -> VerifiedSignature {
+    let creation_time = good
+        .sig
+        .signature_creation_time()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(-1);
+    let notations = good
+        .sig
+        .notation_data()
+        .filter_map(|n| Some((CString::new(n.name()).ok()?, CString::new(n.value()).ok()?)))
+        .collect();
+    VerifiedSignature {
+        signer: CString::new(good.ka.cert().fingerprint().to_hex().as_bytes()).unwrap(),
+        creation_time,
+        notations,
+    }
 }
 
 impl<'a> VerificationHelper for Helper<'a> {
     fn get_certs(&mut self, ids: &[openpgp::KeyHandle]) -> openpgp::Result<Vec<openpgp::Cert>> {
-        let mut certs = Vec::new();
-        for id in ids {
-            let matches = self.certstore.lookup_by_cert_or_subkey(id);
-            for lc in matches? {
-                certs.push(lc.to_cert()?.clone());
-            }
-        }
-        Ok(certs)
+        lookup_certs(&self.certstore, ids)
     }
 
     fn check(&mut self, structure: MessageStructure) -> openpgp::Result<()> {
@@ -213,9 +711,15 @@ impl<'a> VerificationHelper for Helper<'a> {
                     }
                 }
                 MessageLayer::SignatureGroup { ref results } => {
-                    let result = results.iter().find(|r| r.is_ok());
-                    if let Some(result) = result {
-                        self.signer = Some(result.as_ref().unwrap().ka.cert().to_owned());
+                    for result in results {
+                        if let Ok(good) = result {
+                            if self.signer.is_none() {
+                                self.signer = Some(good.ka.cert().to_owned());
+                            }
+                            self.signatures.push(record_signature(good));
+                        }
+                    }
+                    if !self.signatures.is_empty() {
                         return Ok(());
                     }
                 }
@@ -225,6 +729,57 @@ impl<'a> VerificationHelper for Helper<'a> {
     }
 }
 
+struct DecryptHelper<'a, 'b> {
+    certstore: Arc<sequoia_cert_store::CertStore<'a>>,
+    keystore: &'b mut sequoia_keystore::Keystore,
+    password: Option<&'b str>,
+    fingerprint: Option<openpgp::Fingerprint>,
+}
+
+impl<'a, 'b> VerificationHelper for DecryptHelper<'a, 'b> {
+    fn get_certs(&mut self, ids: &[openpgp::KeyHandle]) -> openpgp::Result<Vec<openpgp::Cert>> {
+        lookup_certs(&self.certstore, ids)
+    }
+
+    fn check(&mut self, _structure: MessageStructure) -> openpgp::Result<()> {
+        // Encrypted-only messages carry no signatures to check.
+        Ok(())
+    }
+}
+
+impl<'a, 'b> DecryptionHelper for DecryptHelper<'a, 'b> {
+    fn decrypt(
+        &mut self,
+        pkesks: &[openpgp::packet::PKESK],
+        _skesks: &[openpgp::packet::SKESK],
+        sym_algo: Option<SymmetricAlgorithm>,
+        decrypt: &mut dyn FnMut(Option<SymmetricAlgorithm>, &openpgp::crypto::SessionKey) -> bool,
+    ) -> openpgp::Result<Option<openpgp::Cert>> {
+        for pkesk in pkesks {
+            let handle = KeyHandle::from(pkesk.recipient().clone());
+            let mut keys = match self.keystore.find_key(handle) {
+                Ok(keys) => keys,
+                Err(_) => continue,
+            };
+            for key in &mut keys {
+                if let Some(password) = self.password {
+                    let _ = key.unlock(password.into());
+                }
+                let fingerprint = key.fingerprint();
+                if pkesk
+                    .decrypt(key, sym_algo)
+                    .map(|(algo, session_key)| decrypt(algo, &session_key))
+                    .unwrap_or(false)
+                {
+                    self.fingerprint = Some(fingerprint);
+                    return Ok(None);
+                }
+            }
+        }
+        Err(anyhow::anyhow!("No matching decryption key"))
+    }
+}
+
 pub struct SequoiaSignature {
     data: Vec<u8>,
 }
@@ -232,6 +787,28 @@ pub struct SequoiaSignature {
 pub struct SequoiaVerificationResult {
     content: Vec<u8>,
     signer: CString,
+    signatures: Vec<VerifiedSignature>,
+}
+
+/// A single verified signature retained on a [`SequoiaVerificationResult`]
+/// so callers can enumerate every valid signer.
+struct VerifiedSignature {
+    signer: CString,
+    /// Signature creation time in seconds since the Unix epoch, or -1 when
+    /// the signature carries no creation-time subpacket.
+    creation_time: i64,
+    /// Notation-data subpackets as (name, value) pairs. Values that are not
+    /// valid C strings (e.g. embedded NULs) are dropped.
+    notations: Vec<(CString, CString)>,
+}
+
+pub struct SequoiaEncryptionResult {
+    data: Vec<u8>,
+}
+
+pub struct SequoiaDecryptionResult {
+    content: Vec<u8>,
+    fingerprint: CString,
 }
 
 #[derive(Default)]
@@ -239,6 +816,25 @@ pub struct SequoiaImportResult {
     key_handles: Vec<CString>,
 }
 
+pub struct SequoiaBackendList {
+    backends: Vec<CString>,
+}
+
+pub struct SequoiaKeyInfo {
+    backend: CString,
+    fingerprint: CString,
+    pin_needed: bool,
+}
+
+pub struct SequoiaKeyList {
+    keys: Vec<SequoiaKeyInfo>,
+}
+
+pub struct SequoiaRefreshResult {
+    updated: size_t,
+    revoked: size_t,
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn sequoia_mechanism_new_from_directory<'a>(
     dir_ptr: *const c_char,
@@ -314,6 +910,64 @@ pub unsafe extern "C" fn sequoia_verification_result_get_signer(
     (*result_ptr).signer.as_ptr()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_verification_result_get_signer_count(
+    result_ptr: *const SequoiaVerificationResult,
+) -> size_t {
+    assert!(!result_ptr.is_null());
+    (*result_ptr).signatures.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_verification_result_get_signer_at(
+    result_ptr: *const SequoiaVerificationResult,
+    index: size_t,
+) -> *const c_char {
+    assert!(!result_ptr.is_null());
+    match (*result_ptr).signatures.get(index) {
+        Some(sig) => sig.signer.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_verification_result_get_creation_time_at(
+    result_ptr: *const SequoiaVerificationResult,
+    index: size_t,
+) -> i64 {
+    assert!(!result_ptr.is_null());
+    match (*result_ptr).signatures.get(index) {
+        Some(sig) => sig.creation_time,
+        None => -1,
+    }
+}
+
+/// Return the value of the notation named `name` on the signature at
+/// `index`, or NULL when the signature has no such notation. The returned
+/// pointer is owned by the result and valid until it is freed.
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_verification_result_get_notation_at(
+    result_ptr: *const SequoiaVerificationResult,
+    index: size_t,
+    name_ptr: *const c_char,
+) -> *const c_char {
+    assert!(!result_ptr.is_null());
+    assert!(!name_ptr.is_null());
+    let name = match CStr::from_ptr(name_ptr).to_str() {
+        Ok(name) => name,
+        Err(_) => return ptr::null(),
+    };
+    match (*result_ptr).signatures.get(index) {
+        Some(sig) => sig
+            .notations
+            .iter()
+            .find(|(n, _)| n.to_str().map(|n| n == name).unwrap_or(false))
+            .map(|(_, value)| value.as_ptr())
+            .unwrap_or(ptr::null()),
+        None => ptr::null(),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn sequoia_sign(
     mechanism_ptr: *mut SequoiaMechanism,
@@ -321,12 +975,37 @@ pub unsafe extern "C" fn sequoia_sign(
     password_ptr: *const c_char,
     data_ptr: *const u8,
     data_len: size_t,
+    notation_names_ptr: *const *const c_char,
+    notation_values_ptr: *const *const c_char,
+    notations_len: size_t,
     err_ptr: *mut *mut SequoiaError,
 ) -> *mut SequoiaSignature {
     assert!(!mechanism_ptr.is_null());
     assert!(!key_handle_ptr.is_null());
     assert!(!data_ptr.is_null());
 
+    let mut notations = Vec::with_capacity(notations_len);
+    if notations_len > 0 {
+        assert!(!notation_names_ptr.is_null());
+        assert!(!notation_values_ptr.is_null());
+        let names = slice::from_raw_parts(notation_names_ptr, notations_len);
+        let values = slice::from_raw_parts(notation_values_ptr, notations_len);
+        for (name_ptr, value_ptr) in names.iter().zip(values) {
+            assert!(!name_ptr.is_null());
+            assert!(!value_ptr.is_null());
+            match (
+                CStr::from_ptr(*name_ptr).to_str(),
+                CStr::from_ptr(*value_ptr).to_str(),
+            ) {
+                (Ok(name), Ok(value)) => notations.push((name, value)),
+                (Err(e), _) | (_, Err(e)) => {
+                    set_error_from(err_ptr, e.into());
+                    return ptr::null_mut();
+                }
+            }
+        }
+    }
+
     let key_handle = match CStr::from_ptr(key_handle_ptr).to_str() {
         Ok(key_handle) => key_handle,
         Err(e) => {
@@ -348,7 +1027,7 @@ pub unsafe extern "C" fn sequoia_sign(
     };
 
     let data = slice::from_raw_parts(data_ptr, data_len);
-    match (*mechanism_ptr).sign(key_handle, password, data) {
+    match (*mechanism_ptr).sign(key_handle, password, data, &notations) {
         Ok(signature) => Box::into_raw(Box::new(SequoiaSignature { data: signature })),
         Err(e) => {
             set_error_from(err_ptr, e);
@@ -377,7 +1056,181 @@ pub unsafe extern "C" fn sequoia_verify(
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sequoia_import_result_free(result_ptr: *mut SequoiaImportResult) {
+pub unsafe extern "C" fn sequoia_sign_detached(
+    mechanism_ptr: *mut SequoiaMechanism,
+    key_handle_ptr: *const c_char,
+    password_ptr: *const c_char,
+    data_ptr: *const u8,
+    data_len: size_t,
+    armor: bool,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaSignature {
+    assert!(!mechanism_ptr.is_null());
+    assert!(!key_handle_ptr.is_null());
+    assert!(!data_ptr.is_null());
+
+    let key_handle = match CStr::from_ptr(key_handle_ptr).to_str() {
+        Ok(key_handle) => key_handle,
+        Err(e) => {
+            set_error_from(err_ptr, e.into());
+            return ptr::null_mut();
+        }
+    };
+
+    let password = if password_ptr.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(password_ptr).to_str() {
+            Ok(password) => Some(password),
+            Err(e) => {
+                set_error_from(err_ptr, e.into());
+                return ptr::null_mut();
+            }
+        }
+    };
+
+    let data = slice::from_raw_parts(data_ptr, data_len);
+    match (*mechanism_ptr).sign_detached(key_handle, password, data, armor) {
+        Ok(signature) => Box::into_raw(Box::new(SequoiaSignature { data: signature })),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_verify_detached(
+    mechanism_ptr: *mut SequoiaMechanism,
+    data_ptr: *const u8,
+    data_len: size_t,
+    sig_ptr: *const u8,
+    sig_len: size_t,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaVerificationResult {
+    assert!(!mechanism_ptr.is_null());
+    assert!(!data_ptr.is_null());
+    assert!(!sig_ptr.is_null());
+
+    let data = slice::from_raw_parts(data_ptr, data_len);
+    let signature = slice::from_raw_parts(sig_ptr, sig_len);
+    match (*mechanism_ptr).verify_detached(data, signature) {
+        Ok(result) => Box::into_raw(Box::new(result)),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_encrypt(
+    mechanism_ptr: *mut SequoiaMechanism,
+    recipients_ptr: *const *const c_char,
+    recipients_len: size_t,
+    data_ptr: *const u8,
+    data_len: size_t,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaEncryptionResult {
+    assert!(!mechanism_ptr.is_null());
+    assert!(!data_ptr.is_null());
+    assert!(!recipients_ptr.is_null());
+
+    let mut recipients = Vec::with_capacity(recipients_len);
+    for ptr in slice::from_raw_parts(recipients_ptr, recipients_len) {
+        assert!(!ptr.is_null());
+        match CStr::from_ptr(*ptr).to_str() {
+            Ok(recipient) => recipients.push(recipient),
+            Err(e) => {
+                set_error_from(err_ptr, e.into());
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    let data = slice::from_raw_parts(data_ptr, data_len);
+    match (*mechanism_ptr).encrypt(&recipients, data) {
+        Ok(data) => Box::into_raw(Box::new(SequoiaEncryptionResult { data })),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_encryption_result_free(result_ptr: *mut SequoiaEncryptionResult) {
+    drop(Box::from_raw(result_ptr))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_encryption_result_get_data(
+    result_ptr: *const SequoiaEncryptionResult,
+    data_len: *mut size_t,
+) -> *const u8 {
+    assert!(!result_ptr.is_null());
+    *data_len = (*result_ptr).data.len();
+    (*result_ptr).data.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_decrypt(
+    mechanism_ptr: *mut SequoiaMechanism,
+    ciphertext_ptr: *const u8,
+    ciphertext_len: size_t,
+    password_ptr: *const c_char,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaDecryptionResult {
+    assert!(!mechanism_ptr.is_null());
+    assert!(!ciphertext_ptr.is_null());
+
+    let password = if password_ptr.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(password_ptr).to_str() {
+            Ok(password) => Some(password),
+            Err(e) => {
+                set_error_from(err_ptr, e.into());
+                return ptr::null_mut();
+            }
+        }
+    };
+
+    let ciphertext = slice::from_raw_parts(ciphertext_ptr, ciphertext_len);
+    match (*mechanism_ptr).decrypt(ciphertext, password) {
+        Ok(result) => Box::into_raw(Box::new(result)),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_decryption_result_free(result_ptr: *mut SequoiaDecryptionResult) {
+    drop(Box::from_raw(result_ptr))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_decryption_result_get_content(
+    result_ptr: *const SequoiaDecryptionResult,
+    data_len: *mut size_t,
+) -> *const u8 {
+    assert!(!result_ptr.is_null());
+    *data_len = (*result_ptr).content.len();
+    (*result_ptr).content.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_decryption_result_get_fingerprint(
+    result_ptr: *const SequoiaDecryptionResult,
+) -> *const c_char {
+    assert!(!result_ptr.is_null());
+    (*result_ptr).fingerprint.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_import_result_free(result_ptr: *mut SequoiaImportResult) {
     drop(Box::from_raw(result_ptr))
 }
 
@@ -431,3 +1284,206 @@ pub unsafe extern "C" fn sequoia_import_keys(
         }
     }
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_mechanism_generate_key(
+    mechanism_ptr: *mut SequoiaMechanism,
+    userids_ptr: *const *const c_char,
+    userids_len: size_t,
+    validity_seconds: u64,
+    cipher_suite: SequoiaCipherSuite,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaImportResult {
+    assert!(!mechanism_ptr.is_null());
+
+    let mut userids = Vec::with_capacity(userids_len);
+    if userids_len > 0 {
+        assert!(!userids_ptr.is_null());
+        for ptr in slice::from_raw_parts(userids_ptr, userids_len) {
+            assert!(!ptr.is_null());
+            match CStr::from_ptr(*ptr).to_str() {
+                Ok(userid) => userids.push(userid),
+                Err(e) => {
+                    set_error_from(err_ptr, e.into());
+                    return ptr::null_mut();
+                }
+            }
+        }
+    }
+
+    match (*mechanism_ptr).generate_key(&userids, validity_seconds, cipher_suite) {
+        Ok(result) => Box::into_raw(Box::new(result)),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_mechanism_set_network_policy(
+    mechanism_ptr: *mut SequoiaMechanism,
+    network_policy: SequoiaNetworkPolicy,
+) {
+    assert!(!mechanism_ptr.is_null());
+
+    (*mechanism_ptr).set_network_policy(network_policy);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_mechanism_refresh_certs(
+    mechanism_ptr: *mut SequoiaMechanism,
+    timeout_secs: u64,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaRefreshResult {
+    assert!(!mechanism_ptr.is_null());
+
+    match (*mechanism_ptr).refresh_certs(timeout_secs) {
+        Ok(result) => Box::into_raw(Box::new(result)),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_refresh_result_free(result_ptr: *mut SequoiaRefreshResult) {
+    drop(Box::from_raw(result_ptr))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_refresh_result_get_updated(
+    result_ptr: *const SequoiaRefreshResult,
+) -> size_t {
+    assert!(!result_ptr.is_null());
+
+    (*result_ptr).updated
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_refresh_result_get_revoked(
+    result_ptr: *const SequoiaRefreshResult,
+) -> size_t {
+    assert!(!result_ptr.is_null());
+
+    (*result_ptr).revoked
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_mechanism_list_backends(
+    mechanism_ptr: *mut SequoiaMechanism,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaBackendList {
+    assert!(!mechanism_ptr.is_null());
+
+    match (*mechanism_ptr).list_backends() {
+        Ok(result) => Box::into_raw(Box::new(result)),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_backend_list_free(result_ptr: *mut SequoiaBackendList) {
+    drop(Box::from_raw(result_ptr))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_backend_list_get_count(
+    result_ptr: *const SequoiaBackendList,
+) -> size_t {
+    assert!(!result_ptr.is_null());
+
+    (*result_ptr).backends.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_backend_list_get_id(
+    result_ptr: *const SequoiaBackendList,
+    index: size_t,
+    err_ptr: *mut *mut SequoiaError,
+) -> *const c_char {
+    assert!(!result_ptr.is_null());
+
+    if index >= (*result_ptr).backends.len() {
+        set_error_from(err_ptr, anyhow::anyhow!("No matching backend"));
+        return ptr::null();
+    }
+    (*result_ptr).backends[index].as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_mechanism_list_keys(
+    mechanism_ptr: *mut SequoiaMechanism,
+    err_ptr: *mut *mut SequoiaError,
+) -> *mut SequoiaKeyList {
+    assert!(!mechanism_ptr.is_null());
+
+    match (*mechanism_ptr).list_keys() {
+        Ok(result) => Box::into_raw(Box::new(result)),
+        Err(e) => {
+            set_error_from(err_ptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_key_list_free(result_ptr: *mut SequoiaKeyList) {
+    drop(Box::from_raw(result_ptr))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_key_list_get_count(result_ptr: *const SequoiaKeyList) -> size_t {
+    assert!(!result_ptr.is_null());
+
+    (*result_ptr).keys.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_key_list_get_backend(
+    result_ptr: *const SequoiaKeyList,
+    index: size_t,
+    err_ptr: *mut *mut SequoiaError,
+) -> *const c_char {
+    assert!(!result_ptr.is_null());
+
+    if index >= (*result_ptr).keys.len() {
+        set_error_from(err_ptr, anyhow::anyhow!("No matching key"));
+        return ptr::null();
+    }
+    (*result_ptr).keys[index].backend.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_key_list_get_fingerprint(
+    result_ptr: *const SequoiaKeyList,
+    index: size_t,
+    err_ptr: *mut *mut SequoiaError,
+) -> *const c_char {
+    assert!(!result_ptr.is_null());
+
+    if index >= (*result_ptr).keys.len() {
+        set_error_from(err_ptr, anyhow::anyhow!("No matching key"));
+        return ptr::null();
+    }
+    (*result_ptr).keys[index].fingerprint.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sequoia_key_list_get_pin_needed(
+    result_ptr: *const SequoiaKeyList,
+    index: size_t,
+    err_ptr: *mut *mut SequoiaError,
+) -> bool {
+    assert!(!result_ptr.is_null());
+
+    if index >= (*result_ptr).keys.len() {
+        set_error_from(err_ptr, anyhow::anyhow!("No matching key"));
+        return false;
+    }
+    (*result_ptr).keys[index].pin_needed
+}